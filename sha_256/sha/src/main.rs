@@ -3,22 +3,27 @@ use risc0_zkvm::{
     sha::Sha256,
 };
 
+// Version tag for the structured commit below, so the on-chain callback can
+// parse outputs from different guest images unambiguously.
+const OUTPUT_VERSION: u8 = 1;
+
 fn main() {
-    let mut input_bytes = [0u8; 32];
+    let input_len: u32 = env::read();
+    let mut input_bytes = vec![0u8; input_len as usize];
     env::read_slice(&mut input_bytes);
 
-    // Find the actual string length by looking for the first null byte
-    let actual_length = input_bytes.iter().position(|&x| x == 0).unwrap_or(32);
-    let input = std::str::from_utf8(&input_bytes[..actual_length]).unwrap();
-
-    println!("Input: {}", input);
-
-    let input_digest =
-        Impl::hash_bytes(&[input.as_bytes()].concat());
-    env::commit_slice(&input_digest.as_bytes());
+    println!("Input length: {}", input_len);
 
+    let input_digest = Impl::hash_bytes(&input_bytes);
     let hash_hex = hex::encode(input_digest.as_bytes());
 
     println!("SHA-256 Hash: {}", hash_hex);
-    env::commit_slice(&hash_hex.as_bytes());
-}
\ No newline at end of file
+
+    // Commit a self-describing output: version(1) + payload_len(4, LE) + payload.
+    let payload = hash_hex.as_bytes();
+    let mut committed = Vec::with_capacity(1 + 4 + payload.len());
+    committed.push(OUTPUT_VERSION);
+    committed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    committed.extend_from_slice(payload);
+    env::commit_slice(&committed);
+}
@@ -5,6 +5,7 @@ use solana_program::{
     clock::Clock,
     entrypoint,
     entrypoint::ProgramResult,
+    hash::hashv,
     instruction::AccountMeta,
     msg,
     program::invoke,
@@ -13,6 +14,7 @@ use solana_program::{
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
+    sysvar::instructions::get_instruction_relative,
     sysvar::Sysvar,
 };
 
@@ -24,89 +26,213 @@ const SHA256_IMAGE_ID: &str =
     "75029efa53432a9030e5e76d58fb34dfa786cd0f6182ed0741d635ff5e4f0341";
 const PRIVATE_DATA_URL: &[u8] = b"https://echoserver.dev/server?response=N4IgFgpghgJhBOBnEAuA2mkBjA9gOwBcJCBaAgTwAcIQAaEIgDwIHpKAbKASzxAF0+9AEY4Y5VKArVUDCMzogYUAlBlFEBEAF96G5QFdkKAEwAGU1qA";
 
+// The Bonsol program that is trusted to invoke `handle_claim_callback` on our
+// behalf. We confirm this via the Instructions sysvar (the program id of the
+// top-level instruction whose processing led to this CPI), not by matching a
+// key in the passed-in account list, since a crafted call can include any
+// account it likes.
+const BONSOL_PROGRAM_ID: Pubkey = solana_program::pubkey!("5kFJoYvooxdt6No6sqRZsEGvXwegHhsPJc5jygaaj8Yw");
+
+// Custom error codes surfaced via `ProgramError::Custom` from the escrow
+// instructions, kept distinct so callers can tell claim failures apart.
+const ERR_ALREADY_CLAIMED: u32 = 1;
+const ERR_HASH_MISMATCH: u32 = 2;
+const ERR_UNAUTHORIZED_CALLBACK_SPONSOR: u32 = 3;
+const ERR_ESCROW_PDA_MISMATCH: u32 = 4;
+const ERR_REENTRANT_CLAIM: u32 = 5;
+const ERR_UNAUTHORIZED_CLOSE: u32 = 6;
+const ERR_ESCROW_NOT_CLOSABLE: u32 = 7;
+const ERR_RENT_EXEMPTION_VIOLATION: u32 = 8;
+const ERR_COMMITMENT_REQUIRED: u32 = 9;
+const ERR_CLAIMER_MISMATCH: u32 = 10;
+const ERR_COMMITMENT_MISMATCH: u32 = 11;
+
+/// A panic-free cursor over a byte slice.
+///
+/// Every read is bounds-checked through `slice::get`, so a truncated or
+/// adversarial instruction/account buffer yields `short_read_err` instead of
+/// indexing out of bounds and panicking the program.
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    short_read_err: ProgramError,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8], short_read_err: ProgramError) -> Self {
+        Self {
+            data,
+            pos: 0,
+            short_read_err,
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProgramError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or_else(|| self.short_read_err.clone())?;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .ok_or_else(|| self.short_read_err.clone())?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_fixed<const N: usize>(&mut self) -> Result<[u8; N], ProgramError> {
+        self.read_bytes(N)?
+            .try_into()
+            .map_err(|_| self.short_read_err.clone())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ProgramError> {
+        Ok(self.read_fixed::<1>()?[0])
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16, ProgramError> {
+        Ok(u16::from_le_bytes(self.read_fixed::<2>()?))
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32, ProgramError> {
+        Ok(u32::from_le_bytes(self.read_fixed::<4>()?))
+    }
+
+    fn read_u64_le(&mut self) -> Result<u64, ProgramError> {
+        Ok(u64::from_le_bytes(self.read_fixed::<8>()?))
+    }
+
+    fn read_pubkey(&mut self) -> Result<Pubkey, ProgramError> {
+        Ok(Pubkey::new_from_array(self.read_fixed::<32>()?))
+    }
+}
+
 // Data structures
 #[derive(Debug, Clone)]
 pub struct EscrowAccount {
-    pub seeds: [u8; 32],           // Store the seed used to derive this account
+    pub seeds: [u8; 32],           // Store the seed used to derive this account, zero-padded
+    pub seed_len: u8,              // Actual length of the seed, so it can be re-sliced for PDA re-derivation
     pub amount_lamports: u64,      // Amount to be released to receiver
     pub hash: [u8; 64],            // SHA256 hex string as bytes (64 chars = 64 bytes)
     pub is_claimed: bool,          // Whether the escrow has been claimed
     pub receiver: Option<Pubkey>,  // The receiver (set when claimed)
     pub initializer: Pubkey,       // The account that initialized the escrow
+    pub expiry_slot: u64,          // Slot after which an unclaimed escrow can be closed by the initializer
+    pub commitment: [u8; 32],      // SHA256(preimage || committed_claimer), zeroed when commit-reveal is unused
+    pub committed_claimer: Pubkey, // Only this pubkey may claim when `commitment` is set; Pubkey::default() when unused
+    pub image_id: [u8; 64],        // RISC0 guest image ID (hex), zero-padded; paired with image_id_len
+    pub image_id_len: u8,          // Actual length of image_id; 0 means "use SHA256_IMAGE_ID"
 }
 
 impl EscrowAccount {
-    pub const SIZE: usize = 32 + 8 + 64 + 1 + 1 + 32 + 32; // seeds + amount + hash + is_claimed + option_flag + receiver + initializer
+    pub const SIZE: usize = 32 + 1 + 8 + 64 + 1 + 1 + 32 + 32 + 8 + 32 + 32 + 64 + 1; // seeds + seed_len + amount + hash + is_claimed + option_flag + receiver + initializer + expiry_slot + commitment + committed_claimer + image_id + image_id_len
+
+    /// The real seed bytes used to derive this escrow's PDA (`seeds`, trimmed to `seed_len`).
+    pub fn seed(&self) -> &[u8] {
+        &self.seeds[..self.seed_len as usize]
+    }
+
+    /// Whether this escrow was initialized in commit-reveal mode, i.e. only
+    /// `committed_claimer` may claim it.
+    pub fn has_commitment(&self) -> bool {
+        self.committed_claimer != Pubkey::default()
+    }
+
+    /// The RISC0 guest image ID this escrow gates releases on, falling back
+    /// to `SHA256_IMAGE_ID` for escrows that didn't set one.
+    pub fn image_id_str(&self) -> Result<&str, ProgramError> {
+        if self.image_id_len == 0 {
+            return Ok(SHA256_IMAGE_ID);
+        }
+        std::str::from_utf8(&self.image_id[..self.image_id_len as usize])
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
 
     pub fn pack(&self, dst: &mut [u8]) -> ProgramResult {
         if dst.len() < Self::SIZE {
             return Err(ProgramError::AccountDataTooSmall);
         }
 
-        dst[0..32].copy_from_slice(&self.seeds);
-        dst[32..40].copy_from_slice(&self.amount_lamports.to_le_bytes());
-        dst[40..104].copy_from_slice(&self.hash);
-        dst[104] = if self.is_claimed { 1 } else { 0 };
-        
+        let mut pos = 0;
+        dst[pos..pos + 32].copy_from_slice(&self.seeds);
+        pos += 32;
+        dst[pos] = self.seed_len;
+        pos += 1;
+        dst[pos..pos + 8].copy_from_slice(&self.amount_lamports.to_le_bytes());
+        pos += 8;
+        dst[pos..pos + 64].copy_from_slice(&self.hash);
+        pos += 64;
+        dst[pos] = if self.is_claimed { 1 } else { 0 };
+        pos += 1;
+
         // Pack Option<Pubkey>
         match self.receiver {
             Some(receiver) => {
-                dst[105] = 1; // Some flag
-                dst[106..138].copy_from_slice(&receiver.to_bytes());
+                dst[pos] = 1; // Some flag
+                pos += 1;
+                dst[pos..pos + 32].copy_from_slice(&receiver.to_bytes());
+                pos += 32;
             }
             None => {
-                dst[105] = 0; // None flag
-                dst[106..138].fill(0);
+                dst[pos] = 0; // None flag
+                pos += 1;
+                dst[pos..pos + 32].fill(0);
+                pos += 32;
             }
         }
-        
-        dst[138..170].copy_from_slice(&self.initializer.to_bytes());
+
+        dst[pos..pos + 32].copy_from_slice(&self.initializer.to_bytes());
+        pos += 32;
+        dst[pos..pos + 8].copy_from_slice(&self.expiry_slot.to_le_bytes());
+        pos += 8;
+        dst[pos..pos + 32].copy_from_slice(&self.commitment);
+        pos += 32;
+        dst[pos..pos + 32].copy_from_slice(&self.committed_claimer.to_bytes());
+        pos += 32;
+        dst[pos..pos + 64].copy_from_slice(&self.image_id);
+        pos += 64;
+        dst[pos] = self.image_id_len;
 
         Ok(())
     }
 
     pub fn unpack(src: &[u8]) -> Result<Self, ProgramError> {
-        if src.len() < Self::SIZE {
-            return Err(ProgramError::AccountDataTooSmall);
-        }
-
-        let mut seeds = [0u8; 32];
-        seeds.copy_from_slice(&src[0..32]);
-
-        let amount_lamports = u64::from_le_bytes([
-            src[32], src[33], src[34], src[35], src[36], src[37], src[38], src[39],
-        ]);
-
-        let mut hash = [0u8; 64];
-        hash.copy_from_slice(&src[40..104]);
-
-        let is_claimed = src[104] != 0;
-
-        let receiver = if src[105] != 0 {
-            Some(Pubkey::new_from_array([
-                src[106], src[107], src[108], src[109], src[110], src[111], src[112], src[113],
-                src[114], src[115], src[116], src[117], src[118], src[119], src[120], src[121],
-                src[122], src[123], src[124], src[125], src[126], src[127], src[128], src[129],
-                src[130], src[131], src[132], src[133], src[134], src[135], src[136], src[137],
-            ]))
+        let mut reader = ByteReader::new(src, ProgramError::AccountDataTooSmall);
+
+        let seeds = reader.read_fixed::<32>()?;
+        let seed_len = reader.read_u8()?;
+        let amount_lamports = reader.read_u64_le()?;
+        let hash = reader.read_fixed::<64>()?;
+        let is_claimed = reader.read_u8()? != 0;
+
+        let has_receiver = reader.read_u8()? != 0;
+        let receiver_bytes = reader.read_fixed::<32>()?;
+        let receiver = if has_receiver {
+            Some(Pubkey::new_from_array(receiver_bytes))
         } else {
             None
         };
 
-        let initializer = Pubkey::new_from_array([
-            src[138], src[139], src[140], src[141], src[142], src[143], src[144], src[145],
-            src[146], src[147], src[148], src[149], src[150], src[151], src[152], src[153],
-            src[154], src[155], src[156], src[157], src[158], src[159], src[160], src[161],
-            src[162], src[163], src[164], src[165], src[166], src[167], src[168], src[169],
-        ]);
+        let initializer = reader.read_pubkey()?;
+        let expiry_slot = reader.read_u64_le()?;
+        let commitment = reader.read_fixed::<32>()?;
+        let committed_claimer = reader.read_pubkey()?;
+        let image_id = reader.read_fixed::<64>()?;
+        let image_id_len = reader.read_u8()?;
 
         Ok(Self {
             seeds,
+            seed_len,
             amount_lamports,
             hash,
             is_claimed,
             receiver,
             initializer,
+            expiry_slot,
+            commitment,
+            committed_claimer,
+            image_id,
+            image_id_len,
         })
     }
 }
@@ -128,15 +254,8 @@ impl ExecutionTracker {
     }
 
     pub fn unpack(src: &[u8]) -> Result<Self, ProgramError> {
-        if src.len() < Self::SIZE {
-            return Err(ProgramError::AccountDataTooSmall);
-        }
-        let execution_account = Pubkey::new_from_array([
-            src[0], src[1], src[2], src[3], src[4], src[5], src[6], src[7], src[8], src[9],
-            src[10], src[11], src[12], src[13], src[14], src[15], src[16], src[17], src[18],
-            src[19], src[20], src[21], src[22], src[23], src[24], src[25], src[26], src[27],
-            src[28], src[29], src[30], src[31],
-        ]);
+        let mut reader = ByteReader::new(src, ProgramError::AccountDataTooSmall);
+        let execution_account = reader.read_pubkey()?;
         Ok(Self { execution_account })
     }
 }
@@ -148,16 +267,15 @@ pub fn process_instruction(
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    if instruction_data.is_empty() {
-        return Err(ProgramError::InvalidInstructionData);
-    }
-
-    let (instruction, data) = instruction_data.split_first().unwrap();
+    let (instruction, data) = instruction_data
+        .split_first()
+        .ok_or(ProgramError::InvalidInstructionData)?;
 
     match instruction {
         0 => initialize_escrow(program_id, accounts, data),
         1 => claim_escrow(program_id, accounts, data),
         2 => handle_claim_callback(program_id, accounts, data),
+        3 => close_escrow(program_id, accounts, data),
         _ => Err(ProgramError::InvalidInstructionData),
     }
 }
@@ -165,31 +283,50 @@ pub fn process_instruction(
 // Instruction 0: Initialize escrow
 pub fn initialize_escrow(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
     // Parse instruction data: seed_len(1) + seed + hash_len(1) + hash + amount_lamports(8)
-    if data.len() < 2 {
-        return Err(ProgramError::InvalidInstructionData);
-    }
-
-    let seed_len = data[0] as usize;
-    if data.len() < 1 + seed_len + 1 {
-        return Err(ProgramError::InvalidInstructionData);
-    }
+    // + expiry_slot(8) + has_commitment(1) + [commitment(32) + committed_claimer(32)]
+    // + has_image_id(1) + [image_id_len(1) + image_id]
+    let mut reader = ByteReader::new(data, ProgramError::InvalidInstructionData);
+    let seed_len = reader.read_u8()? as usize;
+    let seed = reader.read_bytes(seed_len)?;
+    let hash_len = reader.read_u8()? as usize;
+    let hash_str = reader.read_bytes(hash_len)?;
+    let amount_lamports = reader.read_u64_le()?;
+    let expiry_slot = reader.read_u64_le()?;
+    let has_commitment = reader.read_u8()? != 0;
+    let (commitment, committed_claimer) = if has_commitment {
+        let commitment = reader.read_fixed::<32>()?;
+        let committed_claimer = reader.read_pubkey()?;
+        if committed_claimer == Pubkey::default() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        (commitment, committed_claimer)
+    } else {
+        ([0u8; 32], Pubkey::default())
+    };
+    let has_image_id = reader.read_u8()? != 0;
+    let (image_id, image_id_len) = if has_image_id {
+        let image_id_len = reader.read_u8()? as usize;
+        if image_id_len == 0 || image_id_len > 64 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let image_id_bytes = reader.read_bytes(image_id_len)?;
+        std::str::from_utf8(image_id_bytes).map_err(|_| ProgramError::InvalidInstructionData)?;
+        let mut arr = [0u8; 64];
+        arr[..image_id_len].copy_from_slice(image_id_bytes);
+        (arr, image_id_len as u8)
+    } else {
+        ([0u8; 64], 0u8)
+    };
 
-    let seed = &data[1..1 + seed_len];
-    let hash_len = data[1 + seed_len] as usize;
-    
-    if data.len() < 1 + seed_len + 1 + hash_len + 8 {
+    // Validate hash is exactly 64 bytes, the length of a hex SHA256 digest;
+    // the bytes themselves are not checked to be hex digits
+    if hash_len != 64 {
         return Err(ProgramError::InvalidInstructionData);
     }
 
-    let hash_str = &data[2 + seed_len..2 + seed_len + hash_len];
-    let amount_lamports = u64::from_le_bytes(
-        data[2 + seed_len + hash_len..2 + seed_len + hash_len + 8]
-            .try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    );
-
-    // Validate hash is exactly 64 hex characters
-    if hash_len != 64 {
+    // PDA seeds must fit in the 32-byte seeds array and are individually
+    // capped at 32 bytes by the runtime, so reject anything outside that now.
+    if seed_len == 0 || seed_len > 32 {
         return Err(ProgramError::InvalidInstructionData);
     }
 
@@ -213,7 +350,7 @@ pub fn initialize_escrow(program_id: &Pubkey, accounts: &[AccountInfo], data: &[
         let space = EscrowAccount::SIZE + 100;
         let rent = Rent::get()?;
         let rent_exempt_lamports = rent.minimum_balance(space);
-        let total_lamports = rent_exempt_lamports + amount_lamports;
+        let total_lamports = rent_exempt_lamports.saturating_add(amount_lamports);
 
         let create_account_ix = system_instruction::create_account(
             initializer.key,
@@ -249,23 +386,28 @@ pub fn initialize_escrow(program_id: &Pubkey, accounts: &[AccountInfo], data: &[
     // Initialize escrow account data
     let mut escrow_data = escrow_account.try_borrow_mut_data()?;
     let mut seeds_array = [0u8; 32];
-    let copy_len = std::cmp::min(seed.len(), 32);
-    seeds_array[..copy_len].copy_from_slice(&seed[..copy_len]);
+    seeds_array[..seed.len()].copy_from_slice(seed);
 
     let mut hash_array = [0u8; 64];
     hash_array.copy_from_slice(hash_str);
 
     let escrow = EscrowAccount {
         seeds: seeds_array,
+        seed_len: seed_len as u8,
         amount_lamports,
         hash: hash_array,
         is_claimed: false,
         receiver: None,
         initializer: *initializer.key,
+        expiry_slot,
+        commitment,
+        committed_claimer,
+        image_id,
+        image_id_len,
     };
     escrow.pack(&mut escrow_data)?;
 
-    msg!("Escrow initialized with,lamports: {:?}, seed: {:?}, hash: {:?}, initializer: {:?}", amount_lamports, seed, hash_str, initializer.key);
+    msg!("Escrow initialized with,lamports: {:?}, seed: {:?}, hash: {:?}, initializer: {:?}, expiry_slot: {:?}", amount_lamports, seed, hash_str, initializer.key, expiry_slot);
     Ok(())
 }
 
@@ -275,44 +417,23 @@ pub fn claim_escrow(
     accounts: &[AccountInfo],
     data: &[u8],
 ) -> ProgramResult {
-    // Parse instruction data: execution_id(16) + bump(1) + tip(8) + expiry_offset(8) + seed_len(1) + seed + preimage_len(2) + preimage
-    if data.len() < 35 {
-        return Err(ProgramError::InvalidInstructionData);
-    }
-
-    let execution_id = std::str::from_utf8(&data[0..16])
+    // Parse instruction data: execution_id(16) + bump(1) + tip(8) + expiry_offset(8) + seed_len(1)
+    // + seed + preimage_len(2) + preimage + verify_input_hash(1)
+    let mut reader = ByteReader::new(data, ProgramError::InvalidInstructionData);
+    let execution_id_bytes = reader.read_fixed::<16>()?;
+    let execution_id = std::str::from_utf8(&execution_id_bytes)
         .map_err(|_| ProgramError::InvalidInstructionData)?;
-    let bump = data[16];
-    let tip = u64::from_le_bytes(
-        data[17..25]
-            .try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    );
-    let expiry_offset = u64::from_le_bytes(
-        data[25..33]
-            .try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    );
-    
-    let seed_len = data[33] as usize;
-    if data.len() < 34 + seed_len + 2 {
+    let _bump = reader.read_u8()?;
+    let tip = reader.read_u64_le()?;
+    let expiry_offset = reader.read_u64_le()?;
+    let seed_len = reader.read_u8()? as usize;
+    let seed = reader.read_bytes(seed_len)?;
+    if seed_len == 0 || seed_len > 32 {
         return Err(ProgramError::InvalidInstructionData);
     }
-    
-    let seed = &data[34..34 + seed_len];
-    let preimage_len = u16::from_le_bytes(
-        data[34 + seed_len..36 + seed_len]
-            .try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    ) as usize;
-    
-    if data.len() < 36 + seed_len + preimage_len {
-        return Err(ProgramError::InvalidInstructionData);
-    }
-    
-    let preimage = &data[36 + seed_len..36 + seed_len + preimage_len];
-    let preimageStr = std::str::from_utf8(&preimage[..]).unwrap();
-    msg!("Preimage to hash: {}", preimageStr);
+    let preimage_len = reader.read_u16_le()? as usize;
+    let preimage = reader.read_bytes(preimage_len)?;
+    let verify_input_hash = reader.read_u8()? != 0;
 
     let account_iter = &mut accounts.iter();
     let payer = next_account_info(account_iter)?;
@@ -324,6 +445,7 @@ pub fn claim_escrow(
     let bonsol_program = next_account_info(account_iter)?;
     let image_id_account = next_account_info(account_iter)?;
     let program_id_account = next_account_info(account_iter)?;
+    let instructions_sysvar = next_account_info(account_iter)?;
 
     if !payer.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
@@ -341,7 +463,31 @@ pub fn claim_escrow(
     drop(escrow_data);
 
     if escrow.is_claimed {
-        return Err(ProgramError::Custom(1)); // Already claimed error
+        return Err(ProgramError::Custom(ERR_ALREADY_CLAIMED));
+    }
+
+    // Commit-reveal mode: the preimage is broadcast in the clear in this very
+    // transaction, so anyone watching the mempool could otherwise copy it and
+    // submit a competing claim with a higher tip before the prover runs. When
+    // the escrow was initialized with a commitment, only the committed
+    // claimer may redeem it. This binding is enforced entirely on-chain: here
+    // the supplied preimage and receiver are checked against the stored
+    // commitment, and the callback checks the receiver against the committed
+    // claimer again; we do not also ask Bonsol to
+    // verify an `input_hash`, since that hash is computed over the raw
+    // execution inputs and has no way to incorporate the claimer pubkey this
+    // commitment binds to.
+    if escrow.has_commitment() {
+        if !verify_input_hash {
+            return Err(ProgramError::Custom(ERR_COMMITMENT_REQUIRED));
+        }
+        if receiver.key != &escrow.committed_claimer {
+            return Err(ProgramError::Custom(ERR_CLAIMER_MISMATCH));
+        }
+        let computed_commitment = hashv(&[preimage, receiver.key.as_ref()]);
+        if computed_commitment.to_bytes() != escrow.commitment {
+            return Err(ProgramError::Custom(ERR_COMMITMENT_MISMATCH));
+        }
     }
 
     // Verify requester PDA
@@ -375,25 +521,37 @@ pub fn claim_escrow(
     let clock = Clock::get()?;
     let expiration = clock.slot.saturating_add(expiry_offset);
 
-    msg!("execution_id: {}, tip: {}, expiration: {}, preimage: {:?}", execution_id, tip, expiration, preimage);
+    msg!("execution_id: {}, tip: {}, expiration: {}", execution_id, tip, expiration);
+
+    // Bonsol's `verify_input_hash` checks its own hash computed over the raw
+    // execution inputs; we don't know that hash ourselves, so we never ask
+    // Bonsol to verify it and instead rely entirely on the on-chain
+    // commit-reveal checks above and in the callback.
+    let execution_config = ExecutionConfig {
+        verify_input_hash: false,
+        input_hash: None,
+        forward_output: true,
+    };
+
+    // The guest reads a length-prefixed input (a u32 length, then that many
+    // raw bytes), so frame the preimage the same way here.
+    let mut framed_preimage = Vec::with_capacity(4 + preimage.len());
+    framed_preimage.extend_from_slice(&(preimage.len() as u32).to_le_bytes());
+    framed_preimage.extend_from_slice(preimage);
 
     // Prepare Bonsol execution
     let bonsol_ix = execute_v1(
         payer.key,
         payer.key,
-        SHA256_IMAGE_ID,
+        escrow.image_id_str()?,
         execution_id,
         vec![
-            InputRef::url(preimage), // The preimage to hash
+            InputRef::url(&framed_preimage), // The length-framed preimage to hash
             InputRef::private(PRIVATE_DATA_URL),
         ],
         tip,
         expiration,
-        ExecutionConfig {
-            verify_input_hash: false,
-            input_hash: None,
-            forward_output: true,
-        },
+        execution_config,
         Some(CallbackConfig {
             program_id: *program_id,
             instruction_prefix: vec![2], // handle_claim_callback instruction
@@ -401,36 +559,26 @@ pub fn claim_escrow(
                 AccountMeta::new(*requester.key, false),      // requester
                 AccountMeta::new(*escrow_account.key, false), // escrow_account (writable)
                 AccountMeta::new(*receiver.key, false),       // receiver (writable)
+                AccountMeta::new_readonly(*instructions_sysvar.key, false), // instructions sysvar
             ],
         }),
         None,
     )
     .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    msg!("requester: {:?}, balance: {:?}", requester.key, requester.lamports());
-    msg!("payer: {:?}, balance: {:?}", payer.key, payer.lamports());
-    msg!("system_program: {:?}, balance: {:?}", system_program.key, system_program.lamports());
-    msg!("execution_account: {:?}, balance: {:?}", execution_account.key, execution_account.lamports());
-    msg!("bonsol_program: {:?}, balance: {:?}", bonsol_program.key, bonsol_program.lamports());
-    msg!("image_id_account: {:?}, balance: {:?}", image_id_account.key, image_id_account.lamports());
-    msg!("escrow_account: {:?}, balance: {:?}", escrow_account.key, escrow_account.lamports());
-    msg!("receiver: {:?}, balance: {:?}", receiver.key, receiver.lamports());
-    msg!("program_id_account: {:?}, balance: {:?}", program_id_account.key, program_id_account.lamports());
-
-    msg!("bump: {}, bump2: {}", bump, bump2);
-
     invoke_signed(
         &bonsol_ix,
         &[
-            requester.clone(),          // requester
-            payer.clone(),              // payer
-            system_program.clone(),     // system_program
-            execution_account.clone(),  // execution_account
-            bonsol_program.clone(),     // bonsol_program
-            image_id_account.clone(),   // image_id
-            escrow_account.clone(),     // escrow_account (for callback)
-            receiver.clone(),           // receiver (for callback)
-            program_id_account.clone(), // program_id (our program)
+            requester.clone(),           // requester
+            payer.clone(),               // payer
+            system_program.clone(),      // system_program
+            execution_account.clone(),   // execution_account
+            bonsol_program.clone(),      // bonsol_program
+            image_id_account.clone(),    // image_id
+            escrow_account.clone(),      // escrow_account (for callback)
+            receiver.clone(),            // receiver (for callback)
+            program_id_account.clone(),  // program_id (our program)
+            instructions_sysvar.clone(), // instructions sysvar (for callback)
         ],
         &[&[execution_id.as_bytes(), &[bump2]]],
     )?;
@@ -448,30 +596,78 @@ pub fn claim_escrow(
 
 // Instruction 2: Handle callback from Bonsol
 pub fn handle_claim_callback(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     accounts: &[AccountInfo],
     data: &[u8],
 ) -> ProgramResult {
     msg!("Handling claim callback...");
 
-    if accounts.len() < 4 {
+    if accounts.len() < 5 {
         return Err(ProgramError::NotEnoughAccountKeys);
     }
 
     let requester = &accounts[1];
     let escrow_account = &accounts[2];
     let receiver = &accounts[3];
+    let instructions_sysvar = &accounts[4];
+
+    // Authenticate the caller via the Instructions sysvar rather than
+    // matching a key in the passed-in account list: a data account simply
+    // appearing in `accounts` proves nothing, since a crafted call can
+    // include any account it likes. Instead confirm the top-level
+    // instruction whose processing led, via CPI, to this callback was
+    // actually issued by the real Bonsol program.
+    if instructions_sysvar.key != &solana_program::sysvar::instructions::ID {
+        return Err(ProgramError::Custom(ERR_UNAUTHORIZED_CALLBACK_SPONSOR));
+    }
+    let calling_ix = get_instruction_relative(0, instructions_sysvar)
+        .map_err(|_| ProgramError::Custom(ERR_UNAUTHORIZED_CALLBACK_SPONSOR))?;
+    if calling_ix.program_id != BONSOL_PROGRAM_ID {
+        return Err(ProgramError::Custom(ERR_UNAUTHORIZED_CALLBACK_SPONSOR));
+    }
 
     if !escrow_account.is_writable || !receiver.is_writable {
         return Err(ProgramError::InvalidInstructionData);
     }
 
+    if escrow_account.owner != program_id {
+        return Err(ProgramError::Custom(ERR_ESCROW_PDA_MISMATCH));
+    }
+
     let requester_data = requester.try_borrow_data()?;
-    let execution_account = Pubkey::try_from(&requester_data[0..32])
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    let mut requester_reader = ByteReader::new(&requester_data, ProgramError::AccountDataTooSmall);
+    let execution_account = requester_reader.read_pubkey()?;
+    drop(requester_data);
+
+    // Load and verify escrow before invoking Bonsol's callback handler, since
+    // we need the escrow's own image ID to validate the execution. The
+    // immutable borrow is dropped right away; the later reentrancy check
+    // unpacks the account's bytes again rather than reusing this snapshot.
+    let escrow_data = escrow_account.try_borrow_data()?;
+    let escrow = EscrowAccount::unpack(&escrow_data)?;
+    drop(escrow_data);
+
+    // Re-derive the escrow PDA from the seeds stored on the account
+    // itself, so a crafted callback account list can't substitute a
+    // different escrow than the one the execution was bound to.
+    let (expected_escrow_pda, _) = Pubkey::find_program_address(&[escrow.seed()], program_id);
+    if escrow_account.key != &expected_escrow_pda {
+        return Err(ProgramError::Custom(ERR_ESCROW_PDA_MISMATCH));
+    }
+
+    if escrow.is_claimed {
+        return Err(ProgramError::Custom(ERR_ALREADY_CLAIMED));
+    }
+
+    // Defense in depth: re-check the commit-reveal binding here too, in
+    // case anything upstream of this callback let a mismatched receiver
+    // through.
+    if escrow.has_commitment() && receiver.key != &escrow.committed_claimer {
+        return Err(ProgramError::Custom(ERR_CLAIMER_MISMATCH));
+    }
 
     let callback_output: BonsolCallback = handle_callback(
-        SHA256_IMAGE_ID,
+        escrow.image_id_str()?,
         &execution_account,
         accounts,
         data,
@@ -485,18 +681,22 @@ pub fn handle_claim_callback(
     msg!("Callback committed outputs length: {:?}", callback_output.committed_outputs.len());
     msg!("Callback committed outputs (bytes): {:?}", callback_output.committed_outputs);
 
+    // The guest commits a self-describing output (version(1) + payload_len(4, LE)
+    // + payload) rather than a bare 64-char hex string, so different guest
+    // images can commit outputs of differing shapes unambiguously.
+    let mut output_reader =
+        ByteReader::new(&callback_output.committed_outputs, ProgramError::InvalidInstructionData);
+    let output_version = output_reader.read_u8()?;
+    if output_version != 1 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let payload_len = output_reader.read_u32_le()? as usize;
+    let payload = output_reader.read_bytes(payload_len)?;
+
     // Parse the hash result from Bonsol
-    if let Ok(computed_hash_str) = std::str::from_utf8(&callback_output.committed_outputs) {
+    if let Ok(computed_hash_str) = std::str::from_utf8(payload) {
         msg!("Computed hash from Bonsol: {}", computed_hash_str);
 
-        // Load and verify escrow
-        let mut escrow_data = escrow_account.try_borrow_mut_data()?;
-        let mut escrow = EscrowAccount::unpack(&escrow_data)?;
-
-        if escrow.is_claimed {
-            return Err(ProgramError::Custom(1)); // Already claimed
-        }
-
         // Convert stored hash bytes to string for comparison
         let stored_hash_str = std::str::from_utf8(&escrow.hash)
             .map_err(|_| ProgramError::InvalidInstructionData)?;
@@ -506,15 +706,37 @@ pub fn handle_claim_callback(
         if computed_hash_str.trim() == stored_hash_str.trim() {
             msg!("Hash verification successful! Releasing escrow...");
 
-            // Transfer lamports from escrow to receiver
+            // Reentrancy guard: re-read is_claimed from a fresh borrow of the
+            // account's live bytes immediately before debiting lamports,
+            // rather than trusting the snapshot unpacked above, so an escrow
+            // already marked claimed can't be released twice.
+            let reentrancy_escrow_data = escrow_account.try_borrow_data()?;
+            let already_claimed = EscrowAccount::unpack(&reentrancy_escrow_data)?.is_claimed;
+            drop(reentrancy_escrow_data);
+            if already_claimed {
+                return Err(ProgramError::Custom(ERR_REENTRANT_CLAIM));
+            }
+
+            // Transfer lamports from escrow to receiver, but never dip the
+            // escrow account below rent-exemption for its remaining data.
             let transfer_lamports = escrow.amount_lamports;
-            
+            let rent = Rent::get()?;
+            let post_transfer_balance = escrow_account
+                .lamports()
+                .checked_sub(transfer_lamports)
+                .ok_or(ProgramError::InsufficientFunds)?;
+            if post_transfer_balance < rent.minimum_balance(escrow_account.data_len()) {
+                return Err(ProgramError::Custom(ERR_RENT_EXEMPTION_VIOLATION));
+            }
+
             **escrow_account.try_borrow_mut_lamports()? -= transfer_lamports;
             **receiver.try_borrow_mut_lamports()? += transfer_lamports;
 
             // Update escrow state
+            let mut escrow = escrow;
             escrow.is_claimed = true;
             escrow.receiver = Some(*receiver.key);
+            let mut escrow_data = escrow_account.try_borrow_mut_data()?;
             escrow.pack(&mut escrow_data)?;
 
             msg!(
@@ -524,7 +746,7 @@ pub fn handle_claim_callback(
             );
         } else {
             msg!("Hash verification failed! Expected: {}, Got: {}", stored_hash_str, computed_hash_str);
-            return Err(ProgramError::Custom(2)); // Hash mismatch error
+            return Err(ProgramError::Custom(ERR_HASH_MISMATCH));
         }
     } else {
         msg!("Could not parse hash from callback output");
@@ -532,4 +754,53 @@ pub fn handle_claim_callback(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Instruction 3: Close escrow and reclaim its rent lamports
+pub fn close_escrow(program_id: &Pubkey, accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    let account_iter = &mut accounts.iter();
+    let initializer = next_account_info(account_iter)?;
+    let escrow_account = next_account_info(account_iter)?;
+
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if escrow_account.owner != program_id {
+        return Err(ProgramError::Custom(ERR_ESCROW_PDA_MISMATCH));
+    }
+
+    let mut escrow_data = escrow_account.try_borrow_mut_data()?;
+    let escrow = EscrowAccount::unpack(&escrow_data)?;
+
+    let (expected_pda, _) = Pubkey::find_program_address(&[escrow.seed()], program_id);
+    if escrow_account.key != &expected_pda {
+        return Err(ProgramError::Custom(ERR_ESCROW_PDA_MISMATCH));
+    }
+
+    if &escrow.initializer != initializer.key {
+        return Err(ProgramError::Custom(ERR_UNAUTHORIZED_CLOSE));
+    }
+
+    let clock = Clock::get()?;
+    let expired = clock.slot > escrow.expiry_slot;
+    if !escrow.is_claimed && !expired {
+        return Err(ProgramError::Custom(ERR_ESCROW_NOT_CLOSABLE));
+    }
+
+    let remaining_lamports = escrow_account.lamports();
+    **escrow_account.try_borrow_mut_lamports()? = 0;
+    **initializer.try_borrow_mut_lamports()? = initializer
+        .lamports()
+        .checked_add(remaining_lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    escrow_data.fill(0);
+
+    msg!(
+        "Escrow closed, {} lamports reclaimed by {}",
+        remaining_lamports,
+        initializer.key
+    );
+    Ok(())
+}